@@ -15,6 +15,12 @@ impl TelnetBackend {
             size,
         }
     }
+
+    /// Update the terminal size reported to `ratatui`, e.g. after a NAWS
+    /// resize event from the client.
+    pub fn resize(&mut self, size: Rect) {
+        self.size = size;
+    }
 }
 
 impl Backend for TelnetBackend {
@@ -5,6 +5,8 @@ use crossterm::{
     style::{Attribute, Color},
     QueueableCommand,
 };
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::Result;
 
@@ -27,30 +29,50 @@ impl Default for Style {
     }
 }
 
+/// A single grid cell.
+///
+/// `ch` holds a whole grapheme cluster rather than a single `char` so that
+/// combining marks and multi-codepoint emoji stay attached to the glyph they
+/// modify. Double-width glyphs (e.g. CJK) occupy this cell plus a
+/// `continuation` cell immediately to the right, which renders nothing and is
+/// skipped when diffing, keeping column indices lined up with what the
+/// terminal actually draws.
 #[derive(Clone, PartialEq)]
 struct Cell {
-    ch: char,
+    ch: String,
+    width: u8,
+    continuation: bool,
     style: Style,
 }
 
 impl Default for Cell {
     fn default() -> Self {
         Cell {
-            ch: ' ',
+            ch: " ".to_string(),
+            width: 1,
+            continuation: false,
             style: Style::default(),
         }
     }
 }
 
+impl Cell {
+    fn continuation(style: Style) -> Self {
+        Cell {
+            ch: String::new(),
+            width: 0,
+            continuation: true,
+            style,
+        }
+    }
+}
+
 struct BufferChange<'a> {
     cell: &'a Cell,
     x: usize,
     y: usize,
 }
 
-// TODO:
-// maybe change this to use Vec<String> for rows.
-// this would allow unicode graphemes but it'll be more complex.
 #[derive(Clone)]
 pub struct RenderBuffer {
     data: Vec<Cell>,
@@ -62,7 +84,7 @@ impl Display for RenderBuffer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for j in 0..self.height {
             let line = &self.data[j * self.width..j * self.width + self.width];
-            let line: String = line.iter().map(|cell| cell.ch).collect();
+            let line: String = line.iter().map(|cell| cell.ch.as_str()).collect();
             write!(f, "{}\n", line)?;
         }
 
@@ -137,16 +159,21 @@ impl RenderBuffer {
         self.data = vec![Cell::default(); self.width * self.height];
     }
 
-    pub fn set_char(&mut self, ch: char, style: Option<&Style>, x: usize, y: usize) -> Result<()> {
-        let idx = self.coord_to_idx(x, y);
-        let cell_to_change = self.data.get_mut(idx).ok_or("Coords out of range.")?;
-
-        *cell_to_change = Cell {
-            style: style.unwrap_or(&Style::default()).clone(),
-            ch,
-        };
+    /// Reset just the cells in the given rectangle, leaving the rest of the
+    /// buffer (e.g. a chat log drawn separately) untouched.
+    pub fn clear_region(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        for row in y..(y + height).min(self.height) {
+            for col in x..(x + width).min(self.width) {
+                let idx = self.coord_to_idx(col, row);
+                self.data[idx] = Cell::default();
+            }
+        }
+    }
 
-        Ok(())
+    pub fn set_char(&mut self, ch: char, style: Option<&Style>, x: usize, y: usize) -> Result<()> {
+        let mut buf = [0u8; 4];
+        let width = ch.width().unwrap_or(1);
+        self.write_grapheme(ch.encode_utf8(&mut buf), width, style, x, y)
     }
 
     pub fn set_text(
@@ -156,17 +183,57 @@ impl RenderBuffer {
         x: usize,
         y: usize,
     ) -> Result<()> {
-        if x + text.chars().count() > self.width {
+        if x + text.width() > self.width {
             return Err(format!(
                 "Text: '{text}' is too long for canvas. {x} + {} exceeds width: {}",
-                text.len(),
+                text.width(),
                 self.width
             )
             .into());
         }
 
-        for (i, ch) in text.chars().enumerate() {
-            self.set_char(ch, style, x + i, y)?;
+        let mut col = x;
+        for grapheme in text.graphemes(true) {
+            let width = grapheme.width().max(1);
+            self.write_grapheme(grapheme, width, style, col, y)?;
+            col += width;
+        }
+
+        Ok(())
+    }
+
+    /// Write a single grapheme cluster, reserving a trailing continuation
+    /// cell if it's double-width. Rejects writes that would split a
+    /// double-width glyph across the right border.
+    fn write_grapheme(
+        &mut self,
+        grapheme: &str,
+        width: usize,
+        style: Option<&Style>,
+        x: usize,
+        y: usize,
+    ) -> Result<()> {
+        if width == 2 && x + 1 >= self.width {
+            return Err(format!(
+                "Grapheme '{grapheme}' at x = {x} would split a double-width glyph across the right border."
+            )
+            .into());
+        }
+
+        let style = style.unwrap_or(&Style::default()).clone();
+        let idx = self.coord_to_idx(x, y);
+        let cell_to_change = self.data.get_mut(idx).ok_or("Coords out of range.")?;
+
+        *cell_to_change = Cell {
+            ch: grapheme.to_string(),
+            width: width as u8,
+            continuation: false,
+            style: style.clone(),
+        };
+
+        if width == 2 {
+            let continuation_idx = self.coord_to_idx(x + 1, y);
+            self.data[continuation_idx] = Cell::continuation(style);
         }
 
         Ok(())
@@ -183,6 +250,11 @@ impl RenderBuffer {
         for y in 0..self.height {
             for x in 0..self.width {
                 let current_cell = self.cell_at(x, y);
+                if current_cell.continuation {
+                    // renders nothing; the glyph to its left already covers it
+                    continue;
+                }
+
                 if current_cell != other.cell_at(x, y) {
                     changes.push(BufferChange {
                         cell: current_cell,
@@ -197,10 +269,18 @@ impl RenderBuffer {
     }
 }
 
+/// Number of scrollback rows reserved at the bottom of the canvas for chat,
+/// not counting the compose line below them.
+const LOG_ROWS: usize = 4;
+/// Scrollback rows plus the one-line compose box under them.
+const RESERVED_ROWS: usize = LOG_ROWS + 1;
+
 pub struct Canvas {
     buffer: RenderBuffer,
     width: usize,
     height: usize,
+    /// Chat scrollback, oldest first, already wrapped to `width`.
+    log: Vec<String>,
 }
 
 impl Canvas {
@@ -209,9 +289,21 @@ impl Canvas {
             buffer: RenderBuffer::new(width, height),
             width,
             height,
+            log: Vec::new(),
         }
     }
 
+    /// Rows available for the game view above the reserved chat log and
+    /// compose line.
+    pub fn playfield_height(&self) -> usize {
+        Self::playfield_height_for(self.height)
+    }
+
+    /// Same as `playfield_height`, without needing a `Canvas` around to ask.
+    pub fn playfield_height_for(height: usize) -> usize {
+        height.saturating_sub(RESERVED_ROWS)
+    }
+
     pub fn redraw<F, W>(&mut self, writer: &mut W, f: F) -> Result<()>
     where
         F: Fn(&mut RenderBuffer) -> Result<()>,
@@ -221,7 +313,52 @@ impl Canvas {
         f(&mut self.buffer)?;
 
         let diff = self.buffer.diff(&old_buffer);
+        Self::flush_diff(writer, diff)
+    }
+
+    /// Append a chat line to the scrollback, wrapping it to the canvas width
+    /// so it can't overflow a row and panic `coord_to_idx`, then redraw only
+    /// the scrollback rows that actually changed.
+    pub fn push_message<W: std::io::Write>(&mut self, writer: &mut W, text: &str) -> Result<()> {
+        self.log.extend(wrap_to_width(text, self.width));
+
+        let visible = LOG_ROWS.min(self.height);
+        if self.log.len() > visible {
+            let excess = self.log.len() - visible;
+            self.log.drain(0..excess);
+        }
+
+        let old_buffer = self.buffer.clone();
+        let log_top = self.height.saturating_sub(RESERVED_ROWS);
 
+        for (i, row) in (log_top..log_top + visible).enumerate() {
+            let line = self.log.get(i).map(String::as_str).unwrap_or("");
+            self.buffer
+                .set_text(&pad_to_width(line, self.width), None, 0, row)?;
+        }
+
+        let diff = self.buffer.diff(&old_buffer);
+        Self::flush_diff(writer, diff)
+    }
+
+    /// Redraw the one-line compose box beneath the scrollback with whatever
+    /// the peer has typed so far.
+    pub fn render_compose<W: std::io::Write>(&mut self, writer: &mut W, buffer: &str) -> Result<()> {
+        if self.height == 0 {
+            return Ok(());
+        }
+
+        let old_buffer = self.buffer.clone();
+        let row = self.height - 1;
+        let text = format!("> {buffer}");
+        self.buffer
+            .set_text(&pad_to_width(&text, self.width), None, 0, row)?;
+
+        let diff = self.buffer.diff(&old_buffer);
+        Self::flush_diff(writer, diff)
+    }
+
+    fn flush_diff<W: std::io::Write>(writer: &mut W, diff: Vec<BufferChange<'_>>) -> Result<()> {
         for BufferChange { cell, x, y } in diff {
             writer
                 .queue(crossterm::cursor::MoveTo(x as u16, y as u16))?
@@ -236,7 +373,7 @@ impl Canvas {
                 } else {
                     Attribute::NormalIntensity
                 }))?
-                .queue(crossterm::style::Print(cell.ch))?;
+                .queue(crossterm::style::Print(cell.ch.as_str()))?;
         }
 
         writer.flush()?;
@@ -245,6 +382,56 @@ impl Canvas {
     }
 }
 
+/// Break `text` into grapheme-wrapped lines no wider than `width` columns, so
+/// a long chat message can't run off the edge of the canvas.
+fn wrap_to_width(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0;
+
+    for grapheme in text.graphemes(true) {
+        let w = grapheme.width().max(1);
+        if line_width + w > width {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+        }
+
+        line.push_str(grapheme);
+        line_width += w;
+    }
+
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Pad (or truncate) `line` to exactly `width` columns, so stale longer
+/// content left over from a previous redraw is fully overwritten and an
+/// over-long compose buffer can't run past the right edge.
+pub(crate) fn pad_to_width(line: &str, width: usize) -> String {
+    let mut padded = String::new();
+    let mut padded_width = 0;
+
+    for grapheme in line.graphemes(true) {
+        let w = grapheme.width().max(1);
+        if padded_width + w > width {
+            break;
+        }
+
+        padded.push_str(grapheme);
+        padded_width += w;
+    }
+
+    if padded_width < width {
+        padded.push_str(&" ".repeat(width - padded_width));
+    }
+
+    padded
+}
+
 pub fn restore_screen() -> Result<Vec<u8>> {
     let mut buf: Vec<u8> = Vec::new();
     buf.queue(crossterm::terminal::LeaveAlternateScreen)?
@@ -262,7 +449,6 @@ pub fn clear_screen() -> Result<Vec<u8>> {
     Ok(buf)
 }
 
-/*
 #[cfg(test)]
 mod test {
     use super::*;
@@ -274,26 +460,32 @@ mod test {
 
         canvas
             .redraw(&mut buf, |ctx| {
-                ctx.set_char('A', 0, 0)?;
-                ctx.set_text("Hello!", 0, 1)?;
+                ctx.set_char('A', None, 0, 0)?;
+                ctx.set_text("Hi", None, 0, 1)?;
                 Ok(())
             })
             .unwrap();
 
-        // remember that ASNI escape codes for moving the cursor are 1-based.
-        assert_eq!(&buf[..7], b"\x1b[1;1HA");
-        assert_eq!(
-            &buf[7..],
-            b"\x1b[2;1HH\x1b[2;2He\x1b[2;3Hl\x1b[2;4Hl\x1b[2;5Ho\x1b[2;6H!"
-        );
+        // Each changed cell carries its own cursor move (1-based) plus style
+        // codes, so just check the moves and characters land in order rather
+        // than pinning the exact styling bytes.
+        let rendered = String::from_utf8_lossy(&buf);
+        let a_pos = rendered.find("\u{1b}[1;1H").expect("move to (0, 0)");
+        let h_pos = rendered.find("\u{1b}[2;1H").expect("move to (0, 1)");
+        let i_pos = rendered.find("\u{1b}[2;2H").expect("move to (1, 1)");
+
+        assert!(a_pos < h_pos && h_pos < i_pos);
+        assert!(rendered[a_pos..h_pos].ends_with('A'));
+        assert!(rendered[h_pos..i_pos].ends_with('H'));
+        assert!(rendered[i_pos..].ends_with('i'));
     }
 
     #[test]
     fn buffer_set_text() {
         let mut rb = RenderBuffer::new(10, 10);
 
-        assert!(rb.set_text(&"@".repeat(10), 0, 0).is_ok());
-        assert!(rb.set_text(&"N".repeat(11), 0, 0).is_err());
+        assert!(rb.set_text(&"@".repeat(10), None, 0, 0).is_ok());
+        assert!(rb.set_text(&"N".repeat(11), None, 0, 0).is_err());
     }
 
     #[test]
@@ -301,15 +493,15 @@ mod test {
         let mut buffer = RenderBuffer::new(3, 3);
         let old_buffer = buffer.clone();
 
-        buffer.set_text("ABC", 0, 0).unwrap();
+        buffer.set_text("ABC", None, 0, 0).unwrap();
 
         let diff = buffer.diff(&old_buffer);
 
         assert!(diff.len() == 3);
 
-        assert!(diff[0].ch == &'A');
-        assert!(diff[1].ch == &'B');
-        assert!(diff[2].ch == &'C');
+        assert!(diff[0].cell.ch == "A");
+        assert!(diff[1].cell.ch == "B");
+        assert!(diff[2].cell.ch == "C");
 
         assert!(diff[0].x == 0);
         assert!(diff[1].x == 1);
@@ -324,8 +516,51 @@ mod test {
     fn buffer_coords() {
         let mut rb = RenderBuffer::new(10, 10);
 
-        let res = std::panic::catch_unwind(move || rb.set_char('A', 3, 20));
+        let res = std::panic::catch_unwind(move || rb.set_char('A', None, 3, 20));
         assert!(res.is_err());
     }
+
+    #[test]
+    fn double_width_glyph_reserves_a_continuation_cell() {
+        let mut rb = RenderBuffer::new(10, 10);
+        rb.set_char('世', None, 2, 0).unwrap();
+
+        let cell = rb.cell_at(2, 0);
+        assert_eq!(cell.ch, "世");
+        assert_eq!(cell.width, 2);
+        assert!(!cell.continuation);
+
+        let continuation = rb.cell_at(3, 0);
+        assert!(continuation.continuation);
+        assert_eq!(continuation.width, 0);
+    }
+
+    #[test]
+    fn double_width_glyph_cannot_split_across_the_right_border() {
+        let mut rb = RenderBuffer::new(10, 10);
+        assert!(rb.set_char('世', None, 9, 0).is_err());
+    }
+
+    #[test]
+    fn continuation_cells_are_skipped_when_diffing() {
+        let mut buffer = RenderBuffer::new(10, 1);
+        let old_buffer = buffer.clone();
+
+        buffer.set_char('世', None, 0, 0).unwrap();
+
+        let diff = buffer.diff(&old_buffer);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].x, 0);
+    }
+
+    #[test]
+    fn set_text_accounts_for_double_width_graphemes() {
+        let mut rb = RenderBuffer::new(4, 1);
+
+        // "世界" is 4 columns wide despite being 2 graphemes; this should
+        // exactly fill the buffer rather than overflow it.
+        assert!(rb.set_text("世界", None, 0, 0).is_ok());
+        assert!(rb.set_text("世界A", None, 0, 0).is_err());
+    }
 }
-*/
+
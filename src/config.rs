@@ -0,0 +1,47 @@
+//! TOML server configuration: bind address plus the room layouts that make
+//! up the world. Loaded once at startup and re-loaded live whenever the
+//! config file changes on disk. Room layouts are given inline here; no
+//! separate map files are read or watched.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::shared::RoomId;
+use crate::Result;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerConfig {
+    pub bind_addr: String,
+    pub default_room: RoomId,
+    pub rooms: HashMap<RoomId, RoomConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoomConfig {
+    pub width: usize,
+    pub height: usize,
+    /// Optional ASCII map, one string per row, purely decorative for now.
+    #[serde(default)]
+    pub layout: Option<Vec<String>>,
+    #[serde(default)]
+    pub spawn: (usize, usize),
+}
+
+impl ServerConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let config: ServerConfig = toml::from_str(&text)?;
+
+        if !config.rooms.contains_key(&config.default_room) {
+            return Err(format!(
+                "default_room '{}' is not defined in [rooms]",
+                config.default_room
+            )
+            .into());
+        }
+
+        Ok(config)
+    }
+}
@@ -1,32 +1,54 @@
 mod backend;
 mod canvas;
+mod config;
 mod peer;
 mod shared;
+mod telnet;
 
-use std::{collections::HashMap, error::Error, net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashMap, error::Error, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration,
+};
 
 use bytes::Bytes;
 use futures::{SinkExt, StreamExt};
+use notify::{RecursiveMode, Watcher};
 use ratatui::layout::Rect;
 use ratatui::Terminal;
 use shared::{PeerData, Shared};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
 use tokio_util::codec::{BytesCodec, Framed};
+use unicode_width::UnicodeWidthStr;
 
 use crate::backend::TelnetBackend;
 use crate::canvas::Canvas;
+use crate::config::ServerConfig;
 use crate::peer::Peer;
 use crate::shared::UserInput;
+use crate::telnet::{Negotiator, TelnetEvent};
 
 type TelnetTerminal = Terminal<TelnetBackend>;
 
 pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// Size assumed when a client never reports NAWS within `NAWS_TIMEOUT`
+/// (e.g. a raw `nc` connection), so that client isn't left hanging forever.
+const DEFAULT_WIDTH: u16 = 80;
+const DEFAULT_HEIGHT: u16 = 24;
+const NAWS_TIMEOUT: Duration = Duration::from_secs(3);
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:8000").await?;
-    let state = Arc::new(Mutex::new(Shared::new()));
+    let config_path: PathBuf = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "config.toml".to_string())
+        .into();
+    let config = ServerConfig::load(&config_path)?;
+
+    let listener = TcpListener::bind(&config.bind_addr).await?;
+    let state = Arc::new(Mutex::new(Shared::new(&config)));
+
+    let _watcher = watch_config(config_path, state.clone())?;
 
     loop {
         let (stream, addr) = listener.accept().await?;
@@ -40,25 +62,116 @@ async fn main() -> Result<()> {
     }
 }
 
+/// Watch `config_path` for changes and rebuild the affected rooms live,
+/// so a map author can edit a room's layout and see it on connected clients
+/// without restarting the server. The returned watcher must be kept alive
+/// for as long as live reload should keep working.
+///
+/// Watches the *parent directory* rather than `config_path` itself: editors
+/// that save by writing a temp file and renaming it over the original (vim
+/// and many others) replace the inode at that path, which silently drops an
+/// inotify watch registered on the file directly. A directory watch survives
+/// the rename, so events are filtered down to ones naming `config_path`.
+fn watch_config(
+    config_path: PathBuf,
+    state: Arc<Mutex<Shared>>,
+) -> Result<notify::RecommendedWatcher> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            let _ = tx.send(event);
+        }
+    })?;
+
+    let watch_dir = config_path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| std::path::Path::new("."));
+    watcher.watch(watch_dir, RecursiveMode::NonRecursive)?;
+
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            let touches_config = event
+                .paths
+                .iter()
+                .any(|p| p.file_name() == config_path.file_name());
+            if !touches_config {
+                continue;
+            }
+
+            match ServerConfig::load(&config_path) {
+                Ok(config) => {
+                    println!("{config_path:?} changed, rebuilding world");
+                    state.lock().await.rebuild_rooms(&config);
+                }
+                Err(e) => {
+                    eprintln!("failed to reload {config_path:?}, err = {e:?}");
+                }
+            }
+        }
+    });
+
+    Ok(watcher)
+}
+
+/// Keep negotiating (WILL/DO/WONT/DONT replies, NAWS subnegotiation) until we
+/// learn the client's window size, however many frames that takes, or fall
+/// back to `DEFAULT_WIDTH`x`DEFAULT_HEIGHT` once `NAWS_TIMEOUT` has passed so
+/// a client that never sends NAWS doesn't hang the connection forever.
+async fn negotiate_size(
+    stream: &mut Framed<TcpStream, BytesCodec>,
+    negotiator: &mut Negotiator,
+) -> Result<(u16, u16)> {
+    let deadline = tokio::time::Instant::now() + NAWS_TIMEOUT;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() {
+            println!("client never reported NAWS, falling back to {DEFAULT_WIDTH}x{DEFAULT_HEIGHT}");
+            return Ok((DEFAULT_WIDTH, DEFAULT_HEIGHT));
+        }
+
+        let next = match tokio::time::timeout(remaining, stream.next()).await {
+            Ok(next) => next,
+            Err(_) => {
+                println!(
+                    "client never reported NAWS, falling back to {DEFAULT_WIDTH}x{DEFAULT_HEIGHT}"
+                );
+                return Ok((DEFAULT_WIDTH, DEFAULT_HEIGHT));
+            }
+        };
+
+        match next {
+            Some(Ok(bytes)) => {
+                let (events, replies) = negotiator.feed(bytes.as_ref());
+                if !replies.is_empty() {
+                    stream.send(Bytes::from(replies)).await?;
+                }
+
+                if let Some(size) = events.iter().find_map(|ev| match ev {
+                    TelnetEvent::Resize(w, h) => Some((*w, *h)),
+                    _ => None,
+                }) {
+                    return Ok(size);
+                }
+            }
+            _ => {
+                return Err("Failed to get options from client.".into());
+            }
+        }
+    }
+}
+
 async fn process(state: Arc<Mutex<Shared>>, stream: TcpStream, addr: SocketAddr) -> Result<()> {
     let mut stream = Framed::new(stream, BytesCodec::new());
+    let mut negotiator = Negotiator::new();
 
-    // set no echo, character mode
     stream
-        .send(Bytes::from_static(&[
-            255, 253, 34, 255, 250, 34, 1, 0, 255, 240, 255, 251, 1,
-        ]))
+        .send(Bytes::from(negotiator.initial_negotiation()))
         .await?;
 
-    // send NAWS
-    stream.send(Bytes::from_static(&[255, 253, 31])).await?;
-
-    let (width, height) = match stream.next().await {
-        Some(Ok(bytes)) => get_telnet_size(bytes.as_ref())?,
-        _ => {
-            return Err("Failed to get options from client.".into());
-        }
-    };
+    let (width, height) = negotiate_size(&mut stream, &mut negotiator).await?;
 
     println!("Got terminal dimensions: w = {}, h = {}", width, height);
 
@@ -77,7 +190,10 @@ async fn process(state: Arc<Mutex<Shared>>, stream: TcpStream, addr: SocketAddr)
     let mut peer = Peer::new(state.clone(), addr, terminal).await;
     let mut canvas = Canvas::new(width as usize, height as usize);
 
-    loop {
+    let login_bytes = render_login_prompt(&mut canvas, "");
+    stream.send(Bytes::from(login_bytes)).await?;
+
+    'session: loop {
         tokio::select! {
             Some(event) = peer.rx.recv() => {
                 let render_bytes = handle_event(event, &mut canvas);
@@ -85,14 +201,25 @@ async fn process(state: Arc<Mutex<Shared>>, stream: TcpStream, addr: SocketAddr)
             }
             res = stream.next() => match res {
                 Some(Ok(msg)) => {
-                    if let Some(event) = peer.handle_input(msg.into()).await {
-                        if matches!(event, UserInput::Quit) {
-                            break;
-                        } else {
-                            state.lock().await.move_peer(&addr, event).unwrap();
-                        }
+                    let (events, replies) = negotiator.feed(msg.as_ref());
+                    if !replies.is_empty() {
+                        stream.send(Bytes::from(replies)).await?;
                     }
 
+                    for telnet_event in events {
+                        let input = match telnet_event {
+                            TelnetEvent::Resize(w, h) => Some(UserInput::Resize(w, h)),
+                            TelnetEvent::Data(bytes) => peer.handle_input(Bytes::from(bytes)).await,
+                        };
+
+                        if let Some(input) = input {
+                            if apply_user_input(input, &mut peer, &mut canvas, &mut stream, &state, addr)
+                                .await?
+                            {
+                                break 'session;
+                            }
+                        }
+                    }
                 },
                 Some(Err(e)) => {}
                 None => break,
@@ -111,40 +238,138 @@ async fn process(state: Arc<Mutex<Shared>>, stream: TcpStream, addr: SocketAddr)
     Ok(())
 }
 
-fn handle_event(ev: RoomEvent, canvas: &mut Canvas) -> Vec<u8> {
+/// Apply one `UserInput` (whether it came from a keystroke or a NAWS
+/// resize) to `peer`/`canvas`/shared state, sending back whatever redraw it
+/// produces. Returns `true` if the session should end.
+async fn apply_user_input(
+    input: UserInput,
+    peer: &mut Peer,
+    canvas: &mut Canvas,
+    stream: &mut Framed<TcpStream, BytesCodec>,
+    state: &Arc<Mutex<Shared>>,
+    addr: SocketAddr,
+) -> Result<bool> {
+    match input {
+        UserInput::Quit => return Ok(true),
+        UserInput::Resize(w, h) => {
+            peer.resize(w, h);
+            *canvas = Canvas::new(w as usize, h as usize);
+            let playfield_height = canvas.playfield_height();
+            state
+                .lock()
+                .await
+                .set_peer_viewport(&addr, w as usize, playfield_height);
+        }
+        UserInput::NameEntry(buffer) => {
+            let render_bytes = render_login_prompt(canvas, &buffer);
+            stream.send(Bytes::from(render_bytes)).await?;
+        }
+        UserInput::Login(name) => {
+            println!("{addr:?} logged in as '{name}'");
+        }
+        UserInput::Compose(buffer) => {
+            let render_bytes = render_compose_prompt(canvas, &buffer);
+            stream.send(Bytes::from(render_bytes)).await?;
+        }
+        UserInput::Say(text) => {
+            if let Err(e) = state.lock().await.say(&addr, text) {
+                eprintln!("failed to send chat message for {addr:?}: {e}");
+            }
+        }
+        move_input => {
+            if let Err(e) = state.lock().await.move_peer(&addr, move_input) {
+                eprintln!("failed to move {addr:?}: {e}");
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Draw the bordered name-entry box a peer sees while still in
+/// `PeerState::Login`, with whatever they've typed so far.
+fn render_login_prompt(canvas: &mut Canvas, name_buffer: &str) -> Vec<u8> {
     let mut buffer = Vec::new();
-    match ev {
-        RoomEvent::PeerMoved(peer_positions) => canvas
-            .redraw(&mut buffer, |ctx| {
-                ctx.clear();
-                for location in &peer_positions {
-                    ctx.set_char('@', None, location.0, location.1)?;
-                }
 
-                Ok(())
-            })
-            .unwrap(),
+    let result = canvas.redraw(&mut buffer, |ctx| {
+        ctx.clear();
+
+        let box_width = 30.min(ctx.width());
+        let box_height = 3.min(ctx.height());
+        let x = (ctx.width() - box_width) / 2;
+        let y = (ctx.height() - box_height) / 2;
+
+        ctx.draw_border(x, y, box_width, box_height, None)?;
+
+        // Clamp the prefix + name to the box's inner width, less the
+        // one-column margin after the left border, so even a
+        // narrow client's canvas (where `MAX_NAME_LEN` columns plus the
+        // prefix wouldn't fit) can't make `set_text` return an `Err`.
+        let prefix = "Name: ";
+        let inner_width = box_width.saturating_sub(2);
+        let available = inner_width.saturating_sub(prefix.width() + 1);
+        let name = canvas::pad_to_width(name_buffer, available);
+        ctx.set_text(&format!("{prefix}{name}"), None, x + 2, y + 1)?;
+
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        eprintln!("failed to render login prompt: {e}");
     }
 
     buffer
 }
 
-fn get_telnet_size(bytes: &[u8]) -> Result<(u16, u16)> {
-    let len = bytes.len();
+/// Redraw the compose line with whatever the peer has typed into it so far.
+fn render_compose_prompt(canvas: &mut Canvas, buffer: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    canvas.render_compose(&mut bytes, buffer).unwrap();
+    bytes
+}
+
+fn handle_event(ev: RoomEvent, canvas: &mut Canvas) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    match ev {
+        RoomEvent::PeerMoved { positions, layout } => {
+            let playfield_height = canvas.playfield_height();
+            canvas
+                .redraw(&mut buffer, |ctx| {
+                    ctx.clear_region(0, 0, ctx.width(), playfield_height);
+
+                    if let Some(layout) = &layout {
+                        for (y, row) in layout.iter().take(playfield_height).enumerate() {
+                            ctx.set_text(&canvas::pad_to_width(row, ctx.width()), None, 0, y)?;
+                        }
+                    }
 
-    // get the naws negotiation
-    // this assumes that the NAWS negotiation always comes last...is this correct?
-    let naws = &bytes[len - 9..];
+                    for location in &positions {
+                        ctx.set_char('@', None, location.0, location.1)?;
+                    }
 
-    let width = (naws[3] as u16) << 8 | naws[4] as u16;
-    let height = (naws[5] as u16) << 8 | naws[6] as u16;
+                    Ok(())
+                })
+                .unwrap();
+        }
+        RoomEvent::Chat { from, text } => {
+            canvas
+                .push_message(&mut buffer, &format!("{from}: {text}"))
+                .unwrap();
+        }
+    }
 
-    Ok((width, height))
+    buffer
 }
 
 #[derive(Clone)]
 pub enum RoomEvent {
-    PeerMoved(Vec<(usize, usize)>),
+    PeerMoved {
+        positions: Vec<(usize, usize)>,
+        /// The room's static ASCII map, if it has one, so the recipient can
+        /// draw it under the peer markers.
+        layout: Option<Vec<String>>,
+    },
+    Chat { from: String, text: String },
 }
 
 pub type Rx = tokio::sync::mpsc::UnboundedReceiver<RoomEvent>;
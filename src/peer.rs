@@ -3,17 +3,30 @@ use std::{net::SocketAddr, sync::Arc};
 use bytes::Bytes;
 use crossterm::ExecutableCommand;
 use ratatui::buffer::Cell;
+use ratatui::layout::Rect;
 use ratatui::widgets::*;
 use ratatui::{buffer::Buffer, widgets::Paragraph};
 use tokio::sync::{mpsc, Mutex};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::shared::{PeerData, UserInput};
+use crate::canvas::Canvas;
+use crate::shared::{PeerData, PeerState, UserInput};
 use crate::{Result, Rx, Shared, TelnetTerminal};
 
+/// Longest display name accepted at the login prompt, in terminal columns
+/// (not characters — a wide glyph like CJK or an emoji counts for 2).
+const MAX_NAME_LEN: usize = 20;
+
 pub struct Peer {
     pub rx: Rx,
+    addr: SocketAddr,
+    state: Arc<Mutex<Shared>>,
     terminal: TelnetTerminal,
     last_buffer: Buffer,
+    name_buffer: String,
+    /// `Some` while the peer has an open chat line (opened with `t`), holding
+    /// whatever they've typed into it so far.
+    compose_buffer: Option<String>,
 }
 
 impl Peer {
@@ -23,32 +36,139 @@ impl Peer {
         terminal: TelnetTerminal,
     ) -> Self {
         let (tx, rx) = mpsc::unbounded_channel();
-
-        state.lock().await.add_peer(addr, tx);
-
         let size = terminal.size().unwrap();
+        let viewport = (
+            size.width as usize,
+            Canvas::playfield_height_for(size.height as usize),
+        );
+
+        state.lock().await.add_peer(addr, tx, viewport);
 
         Peer {
             rx,
+            addr,
+            state,
             terminal,
             last_buffer: Buffer::empty(size),
+            name_buffer: String::new(),
+            compose_buffer: None,
         }
     }
 
+    /// Rebuild the terminal backend with a new size, e.g. after the client
+    /// reports a NAWS resize.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        let size = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+
+        self.terminal.backend_mut().resize(size);
+        self.last_buffer = Buffer::empty(size);
+    }
+
     pub async fn handle_input(&mut self, input: Bytes) -> Option<UserInput> {
         if &input[..] == b"\x1b" {
             return Some(UserInput::Quit);
         }
 
+        let still_logging_in = matches!(
+            self.state.lock().await.peer_state(&self.addr),
+            Some(PeerState::Login)
+        );
+
+        if still_logging_in {
+            return self.handle_login_input(&input).await;
+        }
+
+        if self.compose_buffer.is_some() {
+            return self.handle_compose_input(&input);
+        }
+
         match &input[..] {
             b"w" => Some(UserInput::MoveUp),
             b"a" => Some(UserInput::MoveLeft),
             b"d" => Some(UserInput::MoveRight),
             b"s" => Some(UserInput::MoveDown),
+            b"t" => {
+                self.compose_buffer = Some(String::new());
+                Some(UserInput::Compose(String::new()))
+            }
             _ => None,
         }
     }
 
+    /// Collect characters typed into an open chat line, handling backspace
+    /// and submission on Enter.
+    fn handle_compose_input(&mut self, input: &[u8]) -> Option<UserInput> {
+        match input {
+            b"\r" | b"\n" => {
+                let text = self.compose_buffer.take().unwrap_or_default();
+                if text.is_empty() {
+                    None
+                } else {
+                    Some(UserInput::Say(text))
+                }
+            }
+            b"\x7f" | b"\x08" => {
+                let buffer = self.compose_buffer.as_mut()?;
+                buffer.pop();
+                Some(UserInput::Compose(buffer.clone()))
+            }
+            bytes => {
+                if let Ok(text) = std::str::from_utf8(bytes) {
+                    let buffer = self.compose_buffer.as_mut()?;
+                    for ch in text.chars() {
+                        if !ch.is_control() {
+                            buffer.push(ch);
+                        }
+                    }
+                }
+
+                Some(UserInput::Compose(self.compose_buffer.clone()?))
+            }
+        }
+    }
+
+    /// Collect characters for the name prompt shown to a peer still in
+    /// `PeerState::Login`, handling backspace and submission on Enter.
+    async fn handle_login_input(&mut self, input: &[u8]) -> Option<UserInput> {
+        match input {
+            b"\r" | b"\n" => {
+                if self.name_buffer.is_empty() {
+                    return None;
+                }
+
+                let name = std::mem::take(&mut self.name_buffer);
+                self.state
+                    .lock()
+                    .await
+                    .login_peer(&self.addr, name.clone())
+                    .ok()?;
+
+                Some(UserInput::Login(name))
+            }
+            b"\x7f" | b"\x08" => {
+                self.name_buffer.pop();
+                Some(UserInput::NameEntry(self.name_buffer.clone()))
+            }
+            bytes => {
+                if let Ok(text) = std::str::from_utf8(bytes) {
+                    for ch in text.chars() {
+                        let width = ch.width().unwrap_or(1);
+                        if !ch.is_control() && self.name_buffer.width() + width <= MAX_NAME_LEN {
+                            self.name_buffer.push(ch);
+                        }
+                    }
+                }
+
+                Some(UserInput::NameEntry(self.name_buffer.clone()))
+            }
+        }
+    }
+
     pub fn render(&mut self, state: &PeerData) -> Vec<u8> {
         let next_frame = self
             .terminal
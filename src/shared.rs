@@ -1,5 +1,9 @@
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+};
 
+use crate::config::ServerConfig;
 use crate::{Result, RoomEvent, Tx};
 
 pub enum UserInput {
@@ -8,66 +12,326 @@ pub enum UserInput {
     MoveLeft,
     MoveRight,
     Quit,
+    /// The client's terminal window changed size (NAWS).
+    Resize(u16, u16),
+    /// A peer still at the name prompt typed or deleted a character; carries
+    /// the buffer as composed so far so the prompt can be redrawn.
+    NameEntry(String),
+    /// A peer submitted a non-empty name and has been moved into the world.
+    Login(String),
+    /// A peer composing a chat message typed or deleted a character; carries
+    /// the buffer as composed so far so the compose line can be redrawn.
+    Compose(String),
+    /// A peer submitted a non-empty chat message.
+    Say(String),
+}
+
+pub type RoomId = String;
+
+/// A single playable area: its own peer set, dimensions and (optionally) a
+/// static ASCII layout. Movement is bounded to `width`/`height`, further
+/// narrowed per-peer to whatever their own canvas can actually draw (see
+/// `bounded_dims`), so peers can't walk off the edge of the room *or* their
+/// own terminal and panic `RenderBuffer::coord_to_idx`.
+pub struct Room {
+    pub width: usize,
+    pub height: usize,
+    pub layout: Option<Vec<String>>,
+    pub spawn: (usize, usize),
+    peers: HashSet<SocketAddr>,
+}
+
+impl Room {
+    pub fn new(width: usize, height: usize) -> Self {
+        Room {
+            width,
+            height,
+            layout: None,
+            spawn: (0, 0),
+            peers: HashSet::new(),
+        }
+    }
+
+    pub fn with_layout(width: usize, height: usize, layout: Vec<String>) -> Self {
+        Room {
+            width,
+            height,
+            layout: Some(layout),
+            spawn: (0, 0),
+            peers: HashSet::new(),
+        }
+    }
+
+    pub fn peers(&self) -> &HashSet<SocketAddr> {
+        &self.peers
+    }
 }
 
 pub struct Shared {
     peers: HashMap<SocketAddr, PeerData>,
+    rooms: HashMap<RoomId, Room>,
+    peer_rooms: HashMap<SocketAddr, RoomId>,
+    default_room: RoomId,
 }
 
 impl Shared {
-    pub fn new() -> Self {
-        Shared {
+    pub fn new(config: &ServerConfig) -> Self {
+        let mut shared = Shared {
             peers: HashMap::new(),
+            rooms: HashMap::new(),
+            peer_rooms: HashMap::new(),
+            default_room: config.default_room.clone(),
+        };
+
+        shared.rebuild_rooms(config);
+
+        shared
+    }
+
+    /// (Re)build every room described by `config`, preserving whichever peers
+    /// are currently standing in a room that still exists afterwards. Used
+    /// both at startup and whenever the config file changes on disk.
+    pub fn rebuild_rooms(&mut self, config: &ServerConfig) {
+        let mut rooms = HashMap::new();
+
+        for (room_id, room_cfg) in &config.rooms {
+            let mut room = match &room_cfg.layout {
+                Some(layout) => Room::with_layout(room_cfg.width, room_cfg.height, layout.clone()),
+                None => Room::new(room_cfg.width, room_cfg.height),
+            };
+            room.spawn = room_cfg.spawn;
+
+            if let Some(existing) = self.rooms.remove(room_id) {
+                room.peers = existing.peers;
+            }
+
+            rooms.insert(room_id.clone(), room);
+        }
+
+        self.rooms = rooms;
+        self.default_room = config.default_room.clone();
+
+        // A room a peer was standing in may not have survived the reload;
+        // move it to the default room rather than leaving `peer_rooms`
+        // pointing at a room that no longer exists.
+        let orphaned: Vec<SocketAddr> = self
+            .peer_rooms
+            .iter()
+            .filter(|(_, room_id)| !self.rooms.contains_key(*room_id))
+            .map(|(addr, _)| *addr)
+            .collect();
+
+        for addr in orphaned {
+            self.peer_rooms.remove(&addr);
+            let _ = self.join_room(&addr, self.default_room.clone());
+        }
+
+        let room_ids: Vec<RoomId> = self.rooms.keys().cloned().collect();
+        for room_id in room_ids {
+            self.broadcast_room_positions(&room_id);
         }
     }
 
-    pub fn add_peer(&mut self, socket_addr: SocketAddr, tx: Tx) {
+    /// `viewport` is `(width, playfield_height)` for the peer's own canvas,
+    /// used to bound movement/spawn placement to whichever is smaller: the
+    /// room's configured dimensions or what this peer's terminal can
+    /// actually draw.
+    pub fn add_peer(&mut self, socket_addr: SocketAddr, tx: Tx, viewport: (usize, usize)) {
         self.peers.insert(
             socket_addr,
             PeerData {
                 tx,
-                state: PeerState::Playing,
+                state: PeerState::Login,
+                name: None,
                 position: (0, 0),
+                viewport,
             },
         );
     }
 
-    pub fn move_peer(&mut self, socket_addr: &SocketAddr, input: UserInput) -> Result<()> {
+    /// Record a peer's current canvas size, e.g. after a NAWS resize, and
+    /// re-clamp its position if it no longer fits the (possibly shrunk)
+    /// bounds.
+    pub fn set_peer_viewport(&mut self, socket_addr: &SocketAddr, width: usize, playfield_height: usize) {
+        let Some(peer) = self.peers.get_mut(socket_addr) else {
+            return;
+        };
+        peer.viewport = (width, playfield_height);
+
+        let Some(room_id) = self.peer_rooms.get(socket_addr).cloned() else {
+            return;
+        };
+        let Some(room) = self.rooms.get(&room_id) else {
+            return;
+        };
+        let bounds = bounded_dims((room.width, room.height), (width, playfield_height));
+
+        let peer = self.get_peer_data_mut(socket_addr);
+        peer.position = clamp_to(peer.position, bounds);
+
+        self.broadcast_room_positions(&room_id);
+    }
+
+    /// Complete the login prompt: record the peer's chosen name, move it out
+    /// of `PeerState::Login` and place it in the default room.
+    pub fn login_peer(&mut self, socket_addr: &SocketAddr, name: String) -> Result<()> {
+        {
+            let peer = self
+                .peers
+                .get_mut(socket_addr)
+                .ok_or("Unknown peer")?;
+            peer.name = Some(name);
+            peer.state = PeerState::Playing;
+        }
+
+        self.join_room(socket_addr, self.default_room.clone())?;
+
+        let room_id = self.peer_rooms[socket_addr].clone();
+        self.broadcast_room_positions(&room_id);
+
+        Ok(())
+    }
+
+    pub fn peer_state(&self, socket_addr: &SocketAddr) -> Option<PeerState> {
+        self.peers.get(socket_addr).map(|peer| peer.state)
+    }
+
+    /// Move `socket_addr` into `room_id`, leaving whatever room it was
+    /// previously in and resetting its position to the new room's spawn
+    /// point, clamped to the peer's movement bounds.
+    pub fn join_room(&mut self, socket_addr: &SocketAddr, room_id: RoomId) -> Result<()> {
+        if !self.rooms.contains_key(&room_id) {
+            return Err(format!("No such room: '{room_id}'").into());
+        }
+
+        self.leave_room(socket_addr);
+
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        room.peers.insert(*socket_addr);
+        let (room_width, room_height) = (room.width, room.height);
+        let spawn = room.spawn;
+
+        self.peer_rooms.insert(*socket_addr, room_id);
+
         let peer = self.get_peer_data_mut(socket_addr);
+        let bounds = bounded_dims((room_width, room_height), peer.viewport);
+        peer.position = clamp_to(spawn, bounds);
+
+        Ok(())
+    }
+
+    /// Remove `socket_addr` from whatever room it currently occupies, if any.
+    pub fn leave_room(&mut self, socket_addr: &SocketAddr) {
+        if let Some(room_id) = self.peer_rooms.remove(socket_addr) {
+            if let Some(room) = self.rooms.get_mut(&room_id) {
+                room.peers.remove(socket_addr);
+            }
+        }
+    }
+
+    pub fn move_peer(&mut self, socket_addr: &SocketAddr, input: UserInput) -> Result<()> {
+        let room_id = self
+            .peer_rooms
+            .get(socket_addr)
+            .ok_or("Peer is not in a room")?
+            .clone();
+        let (room_width, room_height) = {
+            let room = self.rooms.get(&room_id).ok_or("Peer's room no longer exists")?;
+            (room.width, room.height)
+        };
 
+        let peer = self.get_peer_data_mut(socket_addr);
+        let (bound_width, bound_height) = bounded_dims((room_width, room_height), peer.viewport);
         let (old_x, old_y) = peer.position;
         let new_pos = match input {
-            UserInput::MoveUp => {
-                if peer.position.1 == 0 {
-                    (old_x, 0)
+            UserInput::MoveUp => (old_x, old_y.saturating_sub(1)),
+            UserInput::MoveDown => {
+                if old_y + 1 >= bound_height {
+                    (old_x, old_y)
                 } else {
-                    (old_x, old_y - 1)
+                    (old_x, old_y + 1)
                 }
             }
-            UserInput::MoveDown => (old_x, old_y + 1),
-            UserInput::MoveLeft => {
-                if peer.position.0 == 0 {
-                    (0, old_y)
+            UserInput::MoveLeft => (old_x.saturating_sub(1), old_y),
+            UserInput::MoveRight => {
+                if old_x + 1 >= bound_width {
+                    (old_x, old_y)
                 } else {
-                    (old_x - 1, old_y)
+                    (old_x + 1, old_y)
                 }
             }
-            UserInput::MoveRight => (old_x + 1, old_y),
             _ => peer.position,
         };
 
         peer.position = new_pos;
 
-        self.broadcast(RoomEvent::PeerMoved(
-            self.peers.iter().map(|peer| peer.1.position).collect(),
-        ));
+        self.broadcast_room_positions(&room_id);
+
+        Ok(())
+    }
+
+    /// Broadcast a chat message from `socket_addr` to every peer sharing its
+    /// room.
+    pub fn say(&mut self, socket_addr: &SocketAddr, text: String) -> Result<()> {
+        let room_id = self
+            .peer_rooms
+            .get(socket_addr)
+            .ok_or("Peer is not in a room")?
+            .clone();
+
+        let from = self
+            .peers
+            .get(socket_addr)
+            .and_then(|peer| peer.name.clone())
+            .unwrap_or_else(|| socket_addr.to_string());
+
+        self.broadcast_to_room(&room_id, RoomEvent::Chat { from, text });
 
         Ok(())
     }
 
-    fn broadcast(&self, ev: RoomEvent) {
-        for (_, data) in &self.peers {
-            let _ = data.tx.send(ev.clone());
+    /// Tell every peer in `room_id` where everyone in that room currently is,
+    /// clamping the positions sent to each recipient against *their own*
+    /// viewport: a roommate's position can be in-bounds for their own
+    /// (larger) canvas but still off the edge of a smaller one, and
+    /// `handle_event` draws these positions straight onto the recipient's
+    /// canvas with no bounds-check of its own.
+    fn broadcast_room_positions(&self, room_id: &RoomId) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+
+        let positions: Vec<(usize, usize)> = room
+            .peers
+            .iter()
+            .map(|addr| self.peers[addr].position)
+            .collect();
+
+        for addr in &room.peers {
+            let Some(recipient) = self.peers.get(addr) else {
+                continue;
+            };
+
+            let bounds = bounded_dims((room.width, room.height), recipient.viewport);
+            let clamped = positions.iter().map(|&pos| clamp_to(pos, bounds)).collect();
+
+            let _ = recipient.tx.send(RoomEvent::PeerMoved {
+                positions: clamped,
+                layout: room.layout.clone(),
+            });
+        }
+    }
+
+    /// Send `ev` to every peer sharing `room_id` with the originating peer.
+    fn broadcast_to_room(&self, room_id: &RoomId, ev: RoomEvent) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+
+        for addr in &room.peers {
+            if let Some(data) = self.peers.get(addr) {
+                let _ = data.tx.send(ev.clone());
+            }
         }
     }
 
@@ -80,10 +344,12 @@ impl Shared {
     }
 
     pub fn remove_peer(&mut self, socket_addr: SocketAddr) {
+        self.leave_room(&socket_addr);
         self.peers.remove(&socket_addr).unwrap();
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum PeerState {
     Login,
     Playing,
@@ -92,5 +358,149 @@ pub enum PeerState {
 pub struct PeerData {
     tx: Tx,
     state: PeerState,
+    name: Option<String>,
     position: (usize, usize),
+    /// `(width, playfield_height)` of this peer's own canvas, last reported
+    /// via `Shared::add_peer`/`Shared::set_peer_viewport`.
+    viewport: (usize, usize),
+}
+
+impl PeerData {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+/// `room_dims` bounded by `viewport`, treating a `0` viewport dimension as
+/// "not yet known" rather than "zero space available".
+fn bounded_dims(room_dims: (usize, usize), viewport: (usize, usize)) -> (usize, usize) {
+    let width = if viewport.0 == 0 {
+        room_dims.0
+    } else {
+        room_dims.0.min(viewport.0)
+    };
+    let height = if viewport.1 == 0 {
+        room_dims.1
+    } else {
+        room_dims.1.min(viewport.1)
+    };
+
+    (width, height)
+}
+
+/// Clamp `pos` so it fits strictly inside a `bounds`-sized area.
+fn clamp_to(pos: (usize, usize), bounds: (usize, usize)) -> (usize, usize) {
+    (
+        pos.0.min(bounds.0.saturating_sub(1)),
+        pos.1.min(bounds.1.saturating_sub(1)),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RoomConfig;
+
+    fn test_config(width: usize, height: usize) -> ServerConfig {
+        let mut rooms = HashMap::new();
+        rooms.insert(
+            "main".to_string(),
+            RoomConfig {
+                width,
+                height,
+                layout: None,
+                spawn: (0, 0),
+            },
+        );
+
+        ServerConfig {
+            bind_addr: "127.0.0.1:0".to_string(),
+            default_room: "main".to_string(),
+            rooms,
+        }
+    }
+
+    fn login(shared: &mut Shared, addr: SocketAddr, viewport: (usize, usize)) -> crate::Rx {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        shared.add_peer(addr, tx, viewport);
+        shared.login_peer(&addr, addr.to_string()).unwrap();
+        rx
+    }
+
+    #[test]
+    fn move_peer_bounds_to_the_smaller_of_room_and_viewport() {
+        let config = test_config(40, 20);
+        let mut shared = Shared::new(&config);
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        login(&mut shared, addr, (10, 5));
+
+        for _ in 0..20 {
+            shared.move_peer(&addr, UserInput::MoveRight).unwrap();
+        }
+        assert_eq!(shared.get_peer_data(&addr).unwrap().position, (9, 0));
+
+        for _ in 0..20 {
+            shared.move_peer(&addr, UserInput::MoveDown).unwrap();
+        }
+        assert_eq!(shared.get_peer_data(&addr).unwrap().position, (9, 4));
+    }
+
+    #[test]
+    fn broadcast_clamps_positions_to_each_recipients_own_viewport() {
+        let config = test_config(40, 20);
+        let mut shared = Shared::new(&config);
+
+        let wide_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        login(&mut shared, wide_addr, (40, 20));
+
+        let narrow_addr: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let mut narrow_rx = login(&mut shared, narrow_addr, (20, 10));
+
+        for _ in 0..30 {
+            shared.move_peer(&wide_addr, UserInput::MoveRight).unwrap();
+        }
+
+        // Drain to the last broadcast the narrow peer received.
+        let mut last = None;
+        while let Ok(ev) = narrow_rx.try_recv() {
+            last = Some(ev);
+        }
+
+        let Some(RoomEvent::PeerMoved { positions, .. }) = last else {
+            panic!("expected a PeerMoved broadcast");
+        };
+
+        // The wide peer walked to column 30, inside the room (width 40),
+        // which must be clamped to the narrow peer's own viewport (width
+        // 20) before it's drawn on the narrow peer's canvas.
+        assert!(positions.iter().all(|&(x, _)| x < 20));
+    }
+
+    #[test]
+    fn rebuild_rooms_migrates_peers_out_of_a_deleted_room() {
+        let config = test_config(40, 20);
+        let mut shared = Shared::new(&config);
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        login(&mut shared, addr, (40, 20));
+
+        let mut rooms = HashMap::new();
+        rooms.insert(
+            "lobby".to_string(),
+            RoomConfig {
+                width: 10,
+                height: 10,
+                layout: None,
+                spawn: (0, 0),
+            },
+        );
+        let new_config = ServerConfig {
+            bind_addr: "127.0.0.1:0".to_string(),
+            default_room: "lobby".to_string(),
+            rooms,
+        };
+
+        shared.rebuild_rooms(&new_config);
+
+        assert_eq!(shared.peer_rooms.get(&addr), Some(&"lobby".to_string()));
+    }
 }
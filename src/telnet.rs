@@ -0,0 +1,287 @@
+//! Incremental Telnet option negotiation (RFC 854) and NAWS (RFC 1073) parsing.
+//!
+//! The server used to blast out a couple of hardcoded IAC byte sequences and
+//! then assume the very next frame from the client was the NAWS reply. That
+//! breaks as soon as a client interleaves other option replies, or a TCP
+//! segment splits an IAC sequence in half. `Negotiator` instead consumes raw
+//! bytes incrementally, keeping any partial sequence buffered across calls,
+//! and hands back the plain-text payload alongside any events (like a window
+//! resize) it recognises along the way.
+
+use std::collections::HashMap;
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+pub const OPT_ECHO: u8 = 1;
+pub const OPT_SGA: u8 = 3;
+pub const OPT_NAWS: u8 = 31;
+
+/// Something a `Negotiator` extracted from the byte stream that the rest of
+/// the server cares about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelnetEvent {
+    /// Plain, non-telnet bytes that should be treated as user input.
+    Data(Vec<u8>),
+    /// A NAWS subnegotiation reporting the client's terminal size.
+    Resize(u16, u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OptionState {
+    Unknown,
+    Agreed,
+    Refused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseState {
+    Text,
+    Iac,
+    Negotiation(u8),
+    SubOption,
+    SubPayload(u8),
+    SubPayloadIac(u8),
+}
+
+/// Incremental telnet command parser with a small per-option negotiation
+/// table, fed chunks of bytes as they arrive off the wire.
+pub struct Negotiator {
+    state: ParseState,
+    sb_buffer: Vec<u8>,
+    options: HashMap<u8, OptionState>,
+}
+
+impl Negotiator {
+    pub fn new() -> Self {
+        Negotiator {
+            state: ParseState::Text,
+            sb_buffer: Vec::new(),
+            options: HashMap::new(),
+        }
+    }
+
+    /// Bytes the server sends on connect, proactively offering the options it
+    /// wants: it will handle echoing itself, suppress go-ahead, and would
+    /// like to know the client's window size.
+    pub fn initial_negotiation(&self) -> Vec<u8> {
+        vec![
+            IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA, IAC, DO, OPT_NAWS,
+        ]
+    }
+
+    /// Feed in the next chunk read from the socket. Returns any events parsed
+    /// out of it, plus raw reply bytes (if any) that should be written back
+    /// to the client.
+    pub fn feed(&mut self, bytes: &[u8]) -> (Vec<TelnetEvent>, Vec<u8>) {
+        let mut events = Vec::new();
+        let mut data = Vec::new();
+        let mut replies = Vec::new();
+
+        for &b in bytes {
+            match self.state {
+                ParseState::Text => {
+                    if b == IAC {
+                        self.state = ParseState::Iac;
+                    } else {
+                        data.push(b);
+                    }
+                }
+                ParseState::Iac => match b {
+                    DO | DONT | WILL | WONT => self.state = ParseState::Negotiation(b),
+                    SB => {
+                        self.sb_buffer.clear();
+                        self.state = ParseState::SubOption;
+                    }
+                    IAC => {
+                        // escaped 0xFF in plain data
+                        data.push(IAC);
+                        self.state = ParseState::Text;
+                    }
+                    _ => {
+                        // other telnet commands (NOP, AYT, ...) carry no option byte
+                        self.state = ParseState::Text;
+                    }
+                },
+                ParseState::Negotiation(cmd) => {
+                    self.handle_negotiation(cmd, b, &mut replies);
+                    self.state = ParseState::Text;
+                }
+                ParseState::SubOption => {
+                    self.sb_buffer.clear();
+                    self.state = ParseState::SubPayload(b);
+                }
+                ParseState::SubPayload(opt) => {
+                    if b == IAC {
+                        self.state = ParseState::SubPayloadIac(opt);
+                    } else {
+                        self.sb_buffer.push(b);
+                    }
+                }
+                ParseState::SubPayloadIac(opt) => match b {
+                    SE => {
+                        self.finish_subnegotiation(opt, &mut events);
+                        self.state = ParseState::Text;
+                    }
+                    IAC => {
+                        // doubled 0xFF inside a subnegotiation payload is a literal byte
+                        self.sb_buffer.push(IAC);
+                        self.state = ParseState::SubPayload(opt);
+                    }
+                    _ => {
+                        // malformed: bail out of the subnegotiation
+                        self.state = ParseState::Text;
+                    }
+                },
+            }
+        }
+
+        if !data.is_empty() {
+            events.push(TelnetEvent::Data(data));
+        }
+
+        (events, replies)
+    }
+
+    fn handle_negotiation(&mut self, cmd: u8, opt: u8, replies: &mut Vec<u8>) {
+        match cmd {
+            WILL => {
+                if opt == OPT_NAWS {
+                    self.options.insert(opt, OptionState::Agreed);
+                    replies.extend_from_slice(&[IAC, DO, opt]);
+                } else {
+                    self.options.insert(opt, OptionState::Refused);
+                    replies.extend_from_slice(&[IAC, DONT, opt]);
+                }
+            }
+            WONT => {
+                self.options.insert(opt, OptionState::Refused);
+            }
+            DO => {
+                if opt == OPT_ECHO || opt == OPT_SGA {
+                    self.options.insert(opt, OptionState::Agreed);
+                } else {
+                    replies.extend_from_slice(&[IAC, WONT, opt]);
+                }
+            }
+            DONT => {
+                self.options.insert(opt, OptionState::Refused);
+            }
+            _ => unreachable!("Negotiation state only entered for DO/DONT/WILL/WONT"),
+        }
+    }
+
+    fn finish_subnegotiation(&mut self, opt: u8, events: &mut Vec<TelnetEvent>) {
+        if opt == OPT_NAWS && self.sb_buffer.len() >= 4 {
+            let width = (self.sb_buffer[0] as u16) << 8 | self.sb_buffer[1] as u16;
+            let height = (self.sb_buffer[2] as u16) << 8 | self.sb_buffer[3] as u16;
+
+            // A 0-width or 0-height report isn't a usable terminal size (it
+            // would hand a 0x0 canvas straight to `Canvas::new`); ignore it
+            // rather than passing it on, so callers either keep waiting for
+            // a real size or time out to a default.
+            if width > 0 && height > 0 {
+                events.push(TelnetEvent::Resize(width, height));
+            }
+        }
+    }
+
+    /// Whether the peer has agreed to the given option (either by sending
+    /// `WILL` to our `DO`, or `DO` to our `WILL`).
+    pub fn option_agreed(&self, opt: u8) -> bool {
+        matches!(self.options.get(&opt), Some(OptionState::Agreed))
+    }
+}
+
+impl Default for Negotiator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain_text_passes_through() {
+        let mut n = Negotiator::new();
+        let (events, replies) = n.feed(b"hello");
+
+        assert_eq!(events, vec![TelnetEvent::Data(b"hello".to_vec())]);
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn iac_sequence_split_across_feed_calls() {
+        let mut n = Negotiator::new();
+
+        // Split the WILL NAWS negotiation across two reads.
+        let (events, replies) = n.feed(&[IAC, WILL]);
+        assert!(events.is_empty());
+        assert!(replies.is_empty());
+
+        let (events, replies) = n.feed(&[OPT_NAWS]);
+        assert!(events.is_empty());
+        assert_eq!(replies, vec![IAC, DO, OPT_NAWS]);
+        assert!(n.option_agreed(OPT_NAWS));
+    }
+
+    #[test]
+    fn will_unknown_option_is_refused() {
+        let mut n = Negotiator::new();
+        let (events, replies) = n.feed(&[IAC, WILL, 99]);
+
+        assert!(events.is_empty());
+        assert_eq!(replies, vec![IAC, DONT, 99]);
+    }
+
+    #[test]
+    fn naws_subnegotiation_reports_resize() {
+        let mut n = Negotiator::new();
+        let (events, _) = n.feed(&[IAC, SB, OPT_NAWS, 0, 80, 0, 24, IAC, SE]);
+
+        assert_eq!(events, vec![TelnetEvent::Resize(80, 24)]);
+    }
+
+    #[test]
+    fn naws_subnegotiation_ignores_a_zero_size_report() {
+        let mut n = Negotiator::new();
+        let (events, _) = n.feed(&[IAC, SB, OPT_NAWS, 0, 0, 0, 0, IAC, SE]);
+
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn naws_subnegotiation_split_across_feed_calls() {
+        let mut n = Negotiator::new();
+
+        let (events, _) = n.feed(&[IAC, SB, OPT_NAWS, 0, 80]);
+        assert!(events.is_empty());
+
+        let (events, _) = n.feed(&[0, 24, IAC, SE]);
+        assert_eq!(events, vec![TelnetEvent::Resize(80, 24)]);
+    }
+
+    #[test]
+    fn doubled_iac_in_subnegotiation_is_a_literal_byte() {
+        let mut n = Negotiator::new();
+        // A width high byte of 0xFF is sent as IAC IAC inside the SB payload.
+        let (events, _) = n.feed(&[IAC, SB, OPT_NAWS, IAC, IAC, 0, 24, 0, IAC, SE]);
+
+        assert_eq!(events, vec![TelnetEvent::Resize(0xFF00, 24 << 8)]);
+    }
+
+    #[test]
+    fn doubled_iac_in_plain_text_is_a_literal_byte() {
+        let mut n = Negotiator::new();
+        let (events, _) = n.feed(&[b'a', IAC, IAC, b'b']);
+
+        assert_eq!(events, vec![TelnetEvent::Data(vec![b'a', IAC, b'b'])]);
+    }
+}